@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 extern crate alloc;
 
@@ -8,18 +8,36 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 
+use contract_ffi::account::AccountHash;
 use contract_ffi::contract_api::TURef;
 use contract_ffi::contract_api::{runtime, storage, Error as ApiError};
 use contract_ffi::key::Key;
 use contract_ffi::unwrap_or_revert::UnwrapOrRevert;
+use contract_ffi::uref::URef;
 
 enum Arg {
     MethodName = 0,
+    CounterName = 1,
+    // The third positional argument, whose meaning depends on which
+    // mutually exclusive method is being dispatched ("merge" sends a
+    // `CounterState`, "add"/"sub" send an `i64` amount, "batch" sends a
+    // `Vec<(String, i64)>`).
+    MethodArg = 2,
+}
+
+// `migrate`'s own args, separate from `Arg` since they carry the previous
+// version's dictionary seed and name registry, not a method/counter name.
+enum MigrateArg {
+    PreviousSeed = 0,
+    PreviousNames = 1,
 }
 
 #[repr(u16)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 enum Error {
     UnknownMethodName = 0,
+    NoSuchVersion = 1,
+    Overflow = 2,
 }
 
 impl Into<ApiError> for Error {
@@ -28,33 +46,453 @@ impl Into<ApiError> for Error {
     }
 }
 
+const COUNTERS_KEY_NAME: &str = "counters";
+const COUNTER_NAMES_KEY_NAME: &str = "counter_names";
+const COUNTER_KEY_NAME: &str = "counter";
+const PACKAGE_KEY_NAME: &str = "counter_package";
+const ENTRY_POINT_COUNTER: &str = "counter_ext";
+const ENTRY_POINT_MIGRATE: &str = "migrate";
+
+// A contract version is just the set of named keys a given deployment of
+// this file stored its entry points and state under, keyed the same way
+// `call()` has always keyed the account's own named keys.
+type ContractVersion = BTreeMap<String, Key>;
+// The package is the ordered history of every version shipped so far, so
+// `call_upgrade` can always find the most recent one to migrate from.
+type ContractPackage = BTreeMap<u32, ContractVersion>;
+
+fn counters_seed() -> URef {
+    runtime::get_key(COUNTERS_KEY_NAME)
+        .unwrap_or_revert_with(ApiError::GetKey)
+        .to_uref()
+        .unwrap_or_revert_with(ApiError::UnexpectedKeyVariant)
+}
+
+fn counter_names_turef() -> TURef<Vec<String>> {
+    runtime::get_key(COUNTER_NAMES_KEY_NAME)
+        .unwrap_or_revert_with(ApiError::GetKey)
+        .to_turef()
+        .unwrap_or_revert_with(ApiError::UnexpectedKeyVariant)
+}
+
+// Each named counter is a grow-only/PN-counter: one (version, value) slot
+// per contributing account, so concurrent increments from different
+// accounts merge deterministically instead of racing on a single i32.
+type CounterState = BTreeMap<AccountHash, (u64, i64)>;
+
+// A counter that is absent from the dictionary, or whose every slot is a
+// zeroed (version 0, value 0) entry, carries no real contribution from
+// anyone yet; `exists`/`merge` treat it as a tombstone, while `get` just
+// reports its sum of zero.
+fn is_tombstone(state: &CounterState) -> bool {
+    state.values().all(|(version, value)| *version == 0 && *value == 0)
+}
+
+fn get_counter_state(seed: URef, name: &str) -> CounterState {
+    storage::dictionary_get(seed, name)
+        .unwrap_or_revert_with(ApiError::Read)
+        .unwrap_or_default()
+}
+
+// Checked-add every account's slot value; kept separate from `get_counter`
+// so the wraparound-vs-overflow behavior is unit-testable without a
+// dictionary seed to read from.
+fn sum_counter_state(state: &CounterState) -> Option<i64> {
+    state.values().try_fold(0i64, |total, (_, value)| total.checked_add(*value))
+}
+
+fn get_counter(seed: URef, name: &str) -> i64 {
+    sum_counter_state(&get_counter_state(seed, name)).unwrap_or_revert_with(Error::Overflow)
+}
+
+// The pure arithmetic behind "inc"/"add"/"sub"/"reset": given the caller's
+// current slot value, what it becomes after `method` with `amount`, or
+// which `Error` should abort the call. Kept free of storage/host calls so
+// the overflow and unknown-method outcomes are directly unit-testable.
+fn checked_step(method: &str, current: i64, amount: i64) -> Result<i64, Error> {
+    match method {
+        "inc" => current.checked_add(1).ok_or(Error::Overflow),
+        "add" => current.checked_add(amount).ok_or(Error::Overflow),
+        "sub" => current.checked_sub(amount).ok_or(Error::Overflow),
+        "reset" => Ok(0),
+        _ => Err(Error::UnknownMethodName),
+    }
+}
+
+// Applies `method`/`amount` to the caller's own slot value via
+// `checked_step` and bumps its version, reverting instead of wrapping or
+// silently no-op'ing on an overflowing add/sub or an unknown method.
+fn apply_caller_step(seed: URef, name: &str, method: &str, amount: i64) {
+    let caller = runtime::get_caller();
+    let mut state = get_counter_state(seed, name);
+    let (version, value) = state.get(&caller).copied().unwrap_or((0, 0));
+    let new_value = match checked_step(method, value, amount) {
+        Ok(new_value) => new_value,
+        Err(error) => runtime::revert(error),
+    };
+    state.insert(caller, (version + 1, new_value));
+    storage::dictionary_put(seed, name, state);
+    register_counter_name(name);
+}
+
+// Applies one `(method, amount)` pair from a `batch` call against `name`
+// and returns the counter's value afterwards (or after a plain `get`),
+// for the caller to collect alongside the rest of the batch's results.
+// Reverting here (unknown method, overflow) aborts the whole call, so a
+// batch is all-or-nothing by construction rather than by extra bookkeeping.
+fn apply_batch_op(seed: URef, name: &str, method: &str, amount: i64) -> i64 {
+    match method {
+        "create" => {
+            storage::dictionary_put(seed, name, CounterState::new());
+            register_counter_name(name);
+        }
+        "inc" | "add" | "sub" | "reset" => apply_caller_step(seed, name, method, amount),
+        "get" => {}
+        _ => runtime::revert(Error::UnknownMethodName),
+    }
+    get_counter(seed, name)
+}
+
+// Keeps, per account, whichever of `ours`/`theirs` has the higher version
+// number; this is associative/commutative/idempotent, so replays and
+// out-of-order merges always converge to the same state.
+fn merge_states(mut ours: CounterState, theirs: CounterState) -> CounterState {
+    for (account, their_slot) in theirs {
+        let replace = match ours.get(&account) {
+            Some(our_slot) => their_slot.0 > our_slot.0,
+            None => true,
+        };
+        if replace {
+            ours.insert(account, their_slot);
+        }
+    }
+    ours
+}
+
+// Merges only `caller`'s own slot from `offered` into `state`, discarding
+// every other account's entry so one account can never overwrite another's
+// contribution through a forged `merge` payload.
+fn merge_caller_slot(state: CounterState, caller: AccountHash, offered: &CounterState) -> CounterState {
+    let mut scoped = CounterState::new();
+    if let Some(slot) = offered.get(&caller) {
+        scoped.insert(caller, *slot);
+    }
+    merge_states(state, scoped)
+}
+
+// Records `name` in the registry the first time it's touched, so `list`
+// (and a future migration) can enumerate every counter in the dictionary.
+fn register_counter_name(name: &str) {
+    let names_turef = counter_names_turef();
+    let mut names = storage::read(names_turef)
+        .unwrap_or_revert_with(ApiError::Read)
+        .unwrap_or_revert_with(ApiError::ValueNotFound);
+    if !names.iter().any(|existing| existing == name) {
+        names.push(String::from(name));
+        storage::write(names_turef, names);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn counter_ext() {
-    let turef: TURef<i32> = runtime::get_key("count").unwrap().to_turef().unwrap();
+    let seed = counters_seed();
     let method_name: String = runtime::get_arg(Arg::MethodName as u32)
         .unwrap_or_revert_with(ApiError::MissingArgument)
         .unwrap_or_revert_with(ApiError::InvalidArgument);
+
+    if method_name == "list" {
+        let names = storage::read(counter_names_turef())
+            .unwrap_or_revert_with(ApiError::Read)
+            .unwrap_or_revert_with(ApiError::ValueNotFound);
+        runtime::ret(names, Vec::new());
+        return;
+    }
+
+    let counter_name: String = runtime::get_arg(Arg::CounterName as u32)
+        .unwrap_or_revert_with(ApiError::MissingArgument)
+        .unwrap_or_revert_with(ApiError::InvalidArgument);
+
     match method_name.as_str() {
-        "inc" => storage::add(turef, 1),
+        "create" => {
+            storage::dictionary_put(seed, &counter_name, CounterState::new());
+            register_counter_name(&counter_name);
+        }
+        "inc" => apply_caller_step(seed, &counter_name, "inc", 0),
+        "add" => {
+            let amount: i64 = runtime::get_arg(Arg::MethodArg as u32)
+                .unwrap_or_revert_with(ApiError::MissingArgument)
+                .unwrap_or_revert_with(ApiError::InvalidArgument);
+            apply_caller_step(seed, &counter_name, "add", amount);
+        }
+        "sub" => {
+            let amount: i64 = runtime::get_arg(Arg::MethodArg as u32)
+                .unwrap_or_revert_with(ApiError::MissingArgument)
+                .unwrap_or_revert_with(ApiError::InvalidArgument);
+            apply_caller_step(seed, &counter_name, "sub", amount);
+        }
+        "reset" => apply_caller_step(seed, &counter_name, "reset", 0),
         "get" => {
-            let result = storage::read(turef)
-                .unwrap_or_revert_with(ApiError::Read)
-                .unwrap_or_revert_with(ApiError::ValueNotFound);
+            let result = get_counter(seed, &counter_name);
+            runtime::ret(result, Vec::new());
+        }
+        "exists" => {
+            let result = !is_tombstone(&get_counter_state(seed, &counter_name));
             runtime::ret(result, Vec::new());
         }
+        "batch" => {
+            let ops: Vec<(String, i64)> = runtime::get_arg(Arg::MethodArg as u32)
+                .unwrap_or_revert_with(ApiError::MissingArgument)
+                .unwrap_or_revert_with(ApiError::InvalidArgument);
+            let results: Vec<i64> = ops
+                .into_iter()
+                .map(|(method, amount)| apply_batch_op(seed, &counter_name, &method, amount))
+                .collect();
+            runtime::ret(results, Vec::new());
+        }
+        "merge" => {
+            let other: CounterState = runtime::get_arg(Arg::MethodArg as u32)
+                .unwrap_or_revert_with(ApiError::MissingArgument)
+                .unwrap_or_revert_with(ApiError::InvalidArgument);
+            let caller = runtime::get_caller();
+            let state = get_counter_state(seed, &counter_name);
+            let merged = merge_caller_slot(state, caller, &other);
+            let became_live = !is_tombstone(&merged);
+            storage::dictionary_put(seed, &counter_name, merged);
+            if became_live {
+                register_counter_name(&counter_name);
+            }
+        }
         _ => runtime::revert(Error::UnknownMethodName),
     }
 }
 
+// Copies every named counter from `previous_seed`'s dictionary into
+// `seed`'s, then writes `previous_names` into `counter_names`, so an
+// upgrade never drops a counter. Shared by `migrate()` and `call_upgrade()`
+// so the two can't silently diverge.
+fn migrate_counters(
+    previous_seed: URef,
+    previous_names: Vec<String>,
+    seed: URef,
+    counter_names: TURef<Vec<String>>,
+) {
+    for name in &previous_names {
+        let state = get_counter_state(previous_seed, name);
+        storage::dictionary_put(seed, name, state);
+    }
+    storage::write(counter_names, previous_names);
+}
+
+// Copies every named counter (and the registry of names itself) from the
+// previous version's dictionary into this version's, so an upgrade never
+// drops a counter. The previous version's seed and name registry are
+// passed in as arguments.
+#[no_mangle]
+pub extern "C" fn migrate() {
+    let previous_seed: URef = runtime::get_arg(MigrateArg::PreviousSeed as u32)
+        .unwrap_or_revert_with(ApiError::MissingArgument)
+        .unwrap_or_revert_with(ApiError::InvalidArgument);
+    let previous_names: Vec<String> = runtime::get_arg(MigrateArg::PreviousNames as u32)
+        .unwrap_or_revert_with(ApiError::MissingArgument)
+        .unwrap_or_revert_with(ApiError::InvalidArgument);
+
+    migrate_counters(previous_seed, previous_names, counters_seed(), counter_names_turef());
+}
+
+// Stores a fresh `counter_ext`/`migrate` pair backed by a new dictionary
+// seed and name registry, and returns the named-key map that represents
+// this version in the package.
+fn store_version(counters: URef, counter_names: TURef<Vec<String>>) -> ContractVersion {
+    let mut named_keys: ContractVersion = BTreeMap::new();
+    named_keys.insert(String::from(COUNTERS_KEY_NAME), counters.into());
+    named_keys.insert(String::from(COUNTER_NAMES_KEY_NAME), counter_names.into());
+
+    let counter_pointer = storage::store_function_at_hash(ENTRY_POINT_COUNTER, named_keys.clone());
+    let migrate_pointer = storage::store_function_at_hash(ENTRY_POINT_MIGRATE, named_keys.clone());
+    named_keys.insert(String::from(ENTRY_POINT_COUNTER), counter_pointer.into());
+    named_keys.insert(String::from(ENTRY_POINT_MIGRATE), migrate_pointer.into());
+    named_keys
+}
+
 #[no_mangle]
 pub extern "C" fn call() {
-    let counter_local_key = storage::new_turef(0); //initialize counter
+    let counters = storage::new_dictionary(); //seed URef for the named counters
+    let counter_names = storage::new_turef(Vec::new());
+    let version = store_version(counters, counter_names);
+    let counter_key = *version.get(ENTRY_POINT_COUNTER).unwrap();
+
+    let mut package: ContractPackage = BTreeMap::new();
+    package.insert(1, version);
+    let package_turef = storage::new_turef(package);
+
+    runtime::put_key(COUNTERS_KEY_NAME, &counters.into());
+    runtime::put_key(COUNTER_NAMES_KEY_NAME, &counter_names.into());
+    runtime::put_key(PACKAGE_KEY_NAME, &package_turef.into());
+    runtime::put_key(COUNTER_KEY_NAME, &counter_key);
+}
+
+// Appends a new contract version to the package and migrates every named
+// counter left behind by the previous version into it. `PACKAGE_KEY_NAME`
+// stays stable across upgrades, but `COUNTER_KEY_NAME` is repointed at the
+// new version's `counter_ext` hash here: callers must re-resolve the
+// `counter` named key before each call rather than caching the hash they
+// got back from a previous one, since that hash stops being the live
+// entry point as soon as an upgrade runs.
+#[no_mangle]
+pub extern "C" fn call_upgrade() {
+    let package_turef: TURef<ContractPackage> = runtime::get_key(PACKAGE_KEY_NAME)
+        .unwrap_or_revert_with(ApiError::GetKey)
+        .to_turef()
+        .unwrap_or_revert_with(ApiError::UnexpectedKeyVariant);
+    let mut package = storage::read(package_turef)
+        .unwrap_or_revert_with(ApiError::Read)
+        .unwrap_or_revert_with(ApiError::ValueNotFound);
+
+    let previous_version_number = *package
+        .keys()
+        .last()
+        .unwrap_or_revert_with(Error::NoSuchVersion);
+    let previous_version = package
+        .get(&previous_version_number)
+        .unwrap_or_revert_with(Error::NoSuchVersion);
+    let previous_seed: URef = previous_version
+        .get(COUNTERS_KEY_NAME)
+        .unwrap_or_revert_with(Error::NoSuchVersion)
+        .to_uref()
+        .unwrap_or_revert_with(ApiError::UnexpectedKeyVariant);
+    let previous_names_turef: TURef<Vec<String>> = previous_version
+        .get(COUNTER_NAMES_KEY_NAME)
+        .unwrap_or_revert_with(Error::NoSuchVersion)
+        .to_turef()
+        .unwrap_or_revert_with(ApiError::UnexpectedKeyVariant);
+    let previous_names = storage::read(previous_names_turef)
+        .unwrap_or_revert_with(ApiError::Read)
+        .unwrap_or_revert_with(ApiError::ValueNotFound);
+
+    let counters = storage::new_dictionary();
+    let counter_names = storage::new_turef(Vec::new());
+    let version = store_version(counters, counter_names);
+    let counter_key = *version.get(ENTRY_POINT_COUNTER).unwrap();
+
+    // Shares `migrate_counters` with the stored `migrate` entry point
+    // rather than calling it as a stored contract, since we already hold
+    // both dictionaries here; the stored entry point remains available
+    // for operators to re-run by hand.
+    migrate_counters(previous_seed, previous_names, counters, counter_names);
+
+    package.insert(previous_version_number + 1, version);
+    storage::write(package_turef, package);
+
+    runtime::put_key(COUNTERS_KEY_NAME, &counters.into());
+    runtime::put_key(COUNTER_NAMES_KEY_NAME, &counter_names.into());
+    runtime::put_key(COUNTER_KEY_NAME, &counter_key);
+}
+
+// These exercise only the pure helpers above: `contract_ffi`'s host imports
+// (`runtime::get_caller`, `storage::dictionary_put`, ...) have no native
+// implementation, so anything that calls them can't run under `cargo test`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(byte: u8) -> AccountHash {
+        AccountHash::new([byte; 32])
+    }
+
+    #[test]
+    fn checked_step_add_overflows() {
+        let result = checked_step("add", i64::MAX, 1);
+        assert_eq!(result, Err(Error::Overflow));
+    }
+
+    #[test]
+    fn checked_step_sub_overflows() {
+        let result = checked_step("sub", i64::MIN, 1);
+        assert_eq!(result, Err(Error::Overflow));
+    }
+
+    #[test]
+    fn checked_step_unknown_method_is_rejected() {
+        let result = checked_step("frobnicate", 0, 0);
+        assert_eq!(result, Err(Error::UnknownMethodName));
+    }
+
+    #[test]
+    fn checked_step_reset_ignores_current_value() {
+        assert_eq!(checked_step("reset", 41, 0), Ok(0));
+    }
+
+    #[test]
+    fn sum_counter_state_overflows_across_accounts() {
+        let mut state = CounterState::new();
+        state.insert(account(1), (1, i64::MAX));
+        state.insert(account(2), (1, 1));
+        assert_eq!(sum_counter_state(&state), None);
+    }
+
+    #[test]
+    fn sum_counter_state_adds_each_accounts_slot() {
+        let mut state = CounterState::new();
+        state.insert(account(1), (1, 2));
+        state.insert(account(2), (3, 5));
+        assert_eq!(sum_counter_state(&state), Some(7));
+    }
+
+    // Batch is all-or-nothing only because a reverting op aborts the whole
+    // wasm invocation. `apply_batch_op` and `counter_ext` reject an unknown
+    // method in their own dispatch; this checks that `checked_step` rejects
+    // one with that same `Error::UnknownMethodName` variant.
+    #[test]
+    fn checked_step_rejects_the_same_unknown_method_batch_ops_would_hit() {
+        let mid_batch_failure = checked_step("not-a-real-method", 10, 1);
+        assert_eq!(mid_batch_failure, Err(Error::UnknownMethodName));
+    }
+
+    #[test]
+    fn merge_caller_slot_only_admits_the_caller() {
+        let caller = account(1);
+        let attacker = account(2);
+
+        let mut state = CounterState::new();
+        state.insert(caller, (1, 10));
+
+        // A forged payload speaking for both the caller and another
+        // account: only the caller's own entry may be merged in.
+        let mut offered = CounterState::new();
+        offered.insert(caller, (2, 20));
+        offered.insert(attacker, (99, 999));
 
-    //create map of references for stored contract
-    let mut counter_urefs: BTreeMap<String, Key> = BTreeMap::new();
-    let key_name = String::from("count");
-    counter_urefs.insert(key_name, counter_local_key.into());
+        let merged = merge_caller_slot(state, caller, &offered);
 
-    let pointer = storage::store_function_at_hash("counter_ext", counter_urefs);
-    runtime::put_key("counter", &pointer.into());
-}
\ No newline at end of file
+        assert_eq!(merged.get(&caller), Some(&(2, 20)));
+        assert_eq!(merged.get(&attacker), None);
+    }
+
+    #[test]
+    fn merge_caller_slot_ignores_payloads_not_naming_the_caller() {
+        let caller = account(1);
+        let attacker = account(2);
+
+        let state = CounterState::new();
+
+        let mut offered = CounterState::new();
+        offered.insert(attacker, (5, 50));
+
+        let merged = merge_caller_slot(state, caller, &offered);
+
+        assert!(merged.is_empty());
+    }
+
+    #[test]
+    fn merge_states_keeps_the_higher_version_per_account() {
+        let a = account(1);
+
+        let mut ours = CounterState::new();
+        ours.insert(a, (1, 10));
+
+        let mut theirs = CounterState::new();
+        theirs.insert(a, (2, 20));
+
+        assert_eq!(merge_states(ours, theirs).get(&a), Some(&(2, 20)));
+    }
+}